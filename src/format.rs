@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::io::{self, Read, Write};
+
+use rmp::Marker;
+
+use crate::{Date, WellAPI, WellProduction};
+
+/// A streaming destination for parsed production records.
+///
+/// Records are handed to the sink one `(api, date)` cell at a time as they
+/// are produced, so no implementation needs the full `HashMap` up front.
+/// `finish` is called exactly once after the last record to flush any
+/// trailing state (closing brackets, buffered writers, ...).
+pub trait ProductionSink {
+    fn write_record(&mut self, api: WellAPI, date: Date, rec: &WellProduction)
+        -> io::Result<()>;
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Tab-separated output, matching the tool's original layout.
+pub struct TsvSink<W: Write> {
+    w: W,
+}
+
+impl<W: Write> TsvSink<W> {
+    pub fn new(mut w: W) -> io::Result<Self> {
+        writeln!(w, "api\tyear\tmonth\toil\tgas\twater")?;
+        Ok(TsvSink { w })
+    }
+}
+
+impl<W: Write> ProductionSink for TsvSink<W> {
+    fn write_record(&mut self, api: WellAPI, date: Date, rec: &WellProduction)
+          -> io::Result<()> {
+        write!(self.w, "{}\t{}\t{}", api, date.year, date.month)?;
+
+        if let Some(oil) = rec.oil {
+            write!(self.w, "\t{}", oil)?;
+        } else {
+            write!(self.w, "\t")?;
+        }
+
+        if let Some(gas) = rec.gas {
+            write!(self.w, "\t{}", gas)?;
+        } else {
+            write!(self.w, "\t")?;
+        }
+
+        if let Some(water) = rec.water {
+            writeln!(self.w, "\t{}", water)?;
+        } else {
+            writeln!(self.w, "\t")?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// RFC 4180 comma-separated output; a field is quoted only when it contains
+/// a comma, a double quote or a line break.
+pub struct CsvSink<W: Write> {
+    w: W,
+}
+
+impl<W: Write> CsvSink<W> {
+    pub fn new(mut w: W) -> io::Result<Self> {
+        write!(w, "api,year,month,oil,gas,water\r\n")?;
+        Ok(CsvSink { w })
+    }
+
+    fn field(&mut self, s: &str) -> io::Result<()> {
+        if s.contains([',', '"', '\r', '\n']) {
+            write!(self.w, "\"{}\"", s.replace('"', "\"\""))
+        } else {
+            write!(self.w, "{}", s)
+        }
+    }
+}
+
+impl<W: Write> ProductionSink for CsvSink<W> {
+    fn write_record(&mut self, api: WellAPI, date: Date, rec: &WellProduction)
+          -> io::Result<()> {
+        self.field(&api.to_string())?;
+        write!(self.w, ",{},{}", date.year, date.month)?;
+
+        for vol in [rec.oil, rec.gas, rec.water] {
+            match vol {
+                Some(v) => write!(self.w, ",{}", v)?,
+                None => write!(self.w, ",")?,
+            }
+        }
+
+        write!(self.w, "\r\n")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+/// Compact binary MessagePack output.
+///
+/// The file is a bare sequence of 8-element arrays, one per well/month cell,
+/// each laid out as `[state, county, well, year, month, oil, gas, water]`
+/// with the three volumes encoded as an MessagePack float or `nil`. This
+/// schema is shared with [`read_msgpack`], which reconstructs the grouped
+/// model so `.mpk` files are a first-class interchange format for
+/// incremental, re-parse-free workflows.
+pub struct MsgpackSink<W: Write> {
+    w: W,
+}
+
+impl<W: Write> MsgpackSink<W> {
+    pub fn new(w: W) -> io::Result<Self> {
+        Ok(MsgpackSink { w })
+    }
+}
+
+fn write_opt(w: &mut impl Write, vol: Option<f64>) -> io::Result<()> {
+    match vol {
+        Some(v) => { rmp::encode::write_f64(w, v)?; },
+        None => { rmp::encode::write_nil(w)?; },
+    }
+    Ok(())
+}
+
+impl<W: Write> ProductionSink for MsgpackSink<W> {
+    fn write_record(&mut self, api: WellAPI, date: Date, rec: &WellProduction)
+          -> io::Result<()> {
+        rmp::encode::write_array_len(&mut self.w, 8)?;
+        rmp::encode::write_u8(&mut self.w, api.state)?;
+        rmp::encode::write_u16(&mut self.w, api.county)?;
+        rmp::encode::write_u32(&mut self.w, api.well)?;
+        rmp::encode::write_u16(&mut self.w, date.year)?;
+        rmp::encode::write_u8(&mut self.w, date.month)?;
+        write_opt(&mut self.w, rec.oil)?;
+        write_opt(&mut self.w, rec.gas)?;
+        write_opt(&mut self.w, rec.water)?;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
+
+fn read_opt(r: &mut impl Read) -> Result<Option<f64>, Box<dyn Error>> {
+    match rmp::decode::read_marker(r).map_err(|e| e.0)? {
+        Marker::Null => Ok(None),
+        Marker::F64 => {
+            let mut b = [0u8; 8];
+            r.read_exact(&mut b)?;
+            Ok(Some(f64::from_be_bytes(b)))
+        },
+        Marker::F32 => {
+            let mut b = [0u8; 4];
+            r.read_exact(&mut b)?;
+            Ok(Some(f32::from_be_bytes(b) as f64))
+        },
+        _ => Err("expected float or nil volume")?,
+    }
+}
+
+/// Read a MessagePack file written by [`MsgpackSink`] back into the grouped
+/// production model. Records overwrite earlier matching `(api, date, phase)`
+/// cells, which is what makes merging several `.mpk` files well-defined.
+pub fn read_msgpack(r: &mut impl Read)
+      -> Result<HashMap<WellAPI, HashMap<Date, WellProduction>>, Box<dyn Error>> {
+    let mut production: HashMap<WellAPI, HashMap<Date, WellProduction>> =
+        HashMap::new();
+
+    loop {
+        match rmp::decode::read_array_len(r) {
+            Ok(8) => {},
+            Ok(n) => Err(format!("expected 8-element record, got {}", n))?,
+            Err(rmp::decode::ValueReadError::InvalidMarkerRead(e))
+                  if e.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(e) => Err(e)?,
+        }
+
+        let api = WellAPI {
+            state: rmp::decode::read_int(r)?,
+            county: rmp::decode::read_int(r)?,
+            well: rmp::decode::read_int(r)?,
+        };
+        let date = Date {
+            year: rmp::decode::read_int(r)?,
+            month: rmp::decode::read_int(r)?,
+        };
+
+        let rec = production.entry(api)
+            .or_default()
+            .entry(date)
+            .or_insert_with(WellProduction::new);
+
+        if let Some(oil) = read_opt(r)? { rec.oil = Some(oil); }
+        if let Some(gas) = read_opt(r)? { rec.gas = Some(gas); }
+        if let Some(water) = read_opt(r)? { rec.water = Some(water); }
+    }
+
+    Ok(production)
+}
+
+/// Newline-delimited JSON: one object per well/month, ready for `jq`.
+pub struct JsonlSink<W: Write> {
+    w: W,
+}
+
+impl<W: Write> JsonlSink<W> {
+    pub fn new(w: W) -> io::Result<Self> {
+        Ok(JsonlSink { w })
+    }
+}
+
+impl<W: Write> ProductionSink for JsonlSink<W> {
+    fn write_record(&mut self, api: WellAPI, date: Date, rec: &WellProduction)
+          -> io::Result<()> {
+        write!(self.w, "{{\"api\":\"{}\",\"year\":{},\"month\":{}",
+            api, date.year, date.month)?;
+
+        for (name, vol) in [("oil", rec.oil), ("gas", rec.gas),
+              ("water", rec.water)] {
+            match vol {
+                Some(v) => write!(self.w, ",\"{}\":{}", name, v)?,
+                None => write!(self.w, ",\"{}\":null", name)?,
+            }
+        }
+
+        writeln!(self.w, "}}")
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.w.flush()
+    }
+}
@@ -1,15 +1,17 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     env,
     error::Error,
     fmt::{self, Display},
     fs::File,
-    io::{self, BufReader, Write},
+    io::{self, BufRead, BufReader, Cursor, Read, Seek, SeekFrom},
     str,
 };
 
 use zip::ZipArchive;
 
+use flate2::read::MultiGzDecoder;
+
 use encoding_rs_io::DecodeReaderBytes;
 
 use quick_xml::{
@@ -17,6 +19,12 @@ use quick_xml::{
     Reader,
 };
 
+mod format;
+
+use format::{
+    read_msgpack, CsvSink, JsonlSink, MsgpackSink, ProductionSink, TsvSink,
+};
+
 const BUF_SIZE: usize = 4096; // 4kb at once
 
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
@@ -38,6 +46,28 @@ impl WellAPI {
     }
 }
 
+impl str::FromStr for WellAPI {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('-').collect();
+        match parts.as_slice() {
+            [state, county, well] => Ok(WellAPI {
+                state: state.parse()?,
+                county: county.parse()?,
+                well: well.parse()?,
+            }),
+            [plain] if plain.len() == 10
+                  && plain.bytes().all(|b| b.is_ascii_digit()) => Ok(WellAPI {
+                state: plain[0..2].parse()?,
+                county: plain[2..5].parse()?,
+                well: plain[5..10].parse()?,
+            }),
+            _ => Err(format!("malformed API number: {}", s))?,
+        }
+    }
+}
+
 #[derive(Copy, Clone, Debug)]
 enum Phase {
     Oil,
@@ -63,6 +93,20 @@ impl Display for Date {
     }
 }
 
+impl str::FromStr for Date {
+    type Err = Box<dyn Error>;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once('-') {
+            Some((year, month)) => Ok(Date {
+                year: year.parse()?,
+                month: month.parse()?,
+            }),
+            None => Err(format!("expected YYYY-MM date, got {}", s))?,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 struct WellProduction {
     pub oil: Option<f64>,
@@ -91,43 +135,121 @@ enum ParserState {
     ReadVolume,
 }
 
-struct WellProductionParser<'a> {
+/// One completed `<wcproduction>` cell: a single well's volumes for a single
+/// production month, emitted as soon as the element closes.
+#[derive(Copy, Clone, Debug)]
+struct ProductionRecord {
+    pub api: WellAPI,
+    pub date: Date,
+    pub oil: Option<f64>,
+    pub gas: Option<f64>,
+    pub water: Option<f64>,
+}
+
+/// A composable query over wells and production months. Each `Some` field is
+/// an independent constraint; a record must satisfy all present constraints.
+/// `None` means "unconstrained", so the default filter matches everything.
+#[derive(Clone, Debug, Default)]
+struct ProductionFilter {
+    pub states: Option<HashSet<u8>>,
+    pub counties: Option<HashSet<u16>>,
+    pub apis: Option<HashSet<WellAPI>>,
+    pub date_range: Option<(Date, Date)>,
+}
+
+impl ProductionFilter {
+    /// Decide whether a well passes the state/county/API constraints. Checked
+    /// at the `ReadAPIWell` transition so non-matching wells skip early.
+    pub fn matches_api(&self, api: WellAPI) -> bool {
+        if let Some(states) = &self.states {
+            if !states.contains(&api.state) {
+                return false;
+            }
+        }
+
+        if let Some(counties) = &self.counties {
+            if !counties.contains(&api.county) {
+                return false;
+            }
+        }
+
+        if let Some(apis) = &self.apis {
+            if !apis.contains(&api) {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Decide whether a production month falls in the requested range,
+    /// inclusive on both ends. Checked once a `Date` is fully read.
+    pub fn matches_date(&self, date: Date) -> bool {
+        match &self.date_range {
+            Some((from, to)) => {
+                let d = (date.year, date.month);
+                d >= (from.year, from.month) && d <= (to.year, to.month)
+            },
+            None => true,
+        }
+    }
+}
+
+struct WellProductionParser {
     state: ParserState,
     phase: Phase,
-    production: HashMap<WellAPI, HashMap<Date, WellProduction>>,
     current_api: WellAPI,
     current_date: Date,
-    api_predicate: Option<&'a dyn Fn(WellAPI) -> bool>,
+    current_prod: WellProduction,
+    filter: ProductionFilter,
 }
 
-impl<'a> WellProductionParser<'a> {
+impl WellProductionParser {
     pub fn new() -> Self {
         WellProductionParser {
             state: ParserState::Between,
             phase: Phase::Oil,
-            production: HashMap::new(),
             current_api: WellAPI::new(),
             current_date: Date::new(),
-            api_predicate: None,
+            current_prod: WellProduction::new(),
+            filter: ProductionFilter::default(),
         }
     }
 
-    pub fn with_predicate(p: &'a dyn Fn(WellAPI) -> bool) -> Self {
+    pub fn with_filter(filter: ProductionFilter) -> Self {
         let mut parser = WellProductionParser::new();
-        parser.api_predicate = Some(p);
+        parser.filter = filter;
         parser
     }
 
-    pub fn finish(self) -> HashMap<WellAPI, HashMap<Date, WellProduction>> {
-        self.production
+    /// Return the state to enter once a production `Date` field closes,
+    /// routing out-of-range months into `ProductionSkip` as soon as the
+    /// `Date` is complete (both year and month populated).
+    fn after_date_field(&self) -> ParserState {
+        if self.current_date.year != 0 && self.current_date.month != 0
+              && !self.filter.matches_date(self.current_date) {
+            ParserState::ProductionSkip
+        } else {
+            ParserState::ProductionHaveAPI
+        }
     }
 
-    pub fn process(&mut self, ev: Event) -> Result<(), Box<dyn Error>> {
+    /// Feed one XML event into the state machine. When the event closes a
+    /// `<wcproduction>` block that passed the filter's API and date checks,
+    /// the completed [`ProductionRecord`] is returned; otherwise `None`.
+    pub fn process(&mut self, ev: Event)
+          -> Result<Option<ProductionRecord>, Box<dyn Error>> {
+        let mut record = None;
+
         self.state = match self.state {
             ParserState::Between => {
                 match ev {
-                    Event::Start(e) if e.local_name() == b"wcproduction" =>
-                        ParserState::ProductionNeedAPI,
+                    Event::Start(e) if e.local_name() == b"wcproduction" => {
+                        self.current_api = WellAPI::new();
+                        self.current_date = Date::new();
+                        self.current_prod = WellProduction::new();
+                        ParserState::ProductionNeedAPI
+                    },
                     _ => ParserState::Between,
                 }
             },
@@ -155,8 +277,16 @@ impl<'a> WellProductionParser<'a> {
                         _ => ParserState::ProductionHaveAPI,
                     },
 
-                    Event::End(e) if e.local_name() == b"wcproduction" =>
-                        ParserState::Between,
+                    Event::End(e) if e.local_name() == b"wcproduction" => {
+                        record = Some(ProductionRecord {
+                            api: self.current_api,
+                            date: self.current_date,
+                            oil: self.current_prod.oil,
+                            gas: self.current_prod.gas,
+                            water: self.current_prod.water,
+                        });
+                        ParserState::Between
+                    },
 
                     _ => ParserState::ProductionHaveAPI,
                 }
@@ -213,10 +343,10 @@ impl<'a> WellProductionParser<'a> {
                     },
 
                     Event::End(e) if e.local_name() == b"api_well_idn" => {
-                        match self.api_predicate {
-                            Some(p) if !p(self.current_api) =>
-                                ParserState::ProductionSkip,
-                            _ => ParserState::ProductionHaveAPI,
+                        if self.filter.matches_api(self.current_api) {
+                            ParserState::ProductionHaveAPI
+                        } else {
+                            ParserState::ProductionSkip
                         }
                     }
 
@@ -234,7 +364,7 @@ impl<'a> WellProductionParser<'a> {
                     },
 
                     Event::End(e) if e.local_name() == b"prodn_mth" =>
-                        ParserState::ProductionHaveAPI,
+                        self.after_date_field(),
 
                     _ => ParserState::ReadMonth,
                 }
@@ -250,7 +380,7 @@ impl<'a> WellProductionParser<'a> {
                     },
 
                     Event::End(e) if e.local_name() == b"prodn_yr" =>
-                        ParserState::ProductionHaveAPI,
+                        self.after_date_field(),
 
                     _ => ParserState::ReadYear,
                 }
@@ -282,15 +412,10 @@ impl<'a> WellProductionParser<'a> {
                             str::from_utf8(&e.unescaped()?)?
                         )?;
 
-                        let mut rec = self.production.entry(self.current_api)
-                            .or_insert_with(HashMap::new)
-                            .entry(self.current_date)
-                            .or_insert_with(WellProduction::new);
-
                         match self.phase {
-                            Phase::Oil => rec.oil = Some(vol),
-                            Phase::Gas => rec.gas = Some(vol),
-                            Phase::Water => rec.water = Some(vol),
+                            Phase::Oil => self.current_prod.oil = Some(vol),
+                            Phase::Gas => self.current_prod.gas = Some(vol),
+                            Phase::Water => self.current_prod.water = Some(vol),
                         };
 
                         ParserState::ReadVolume
@@ -304,68 +429,337 @@ impl<'a> WellProductionParser<'a> {
             },
         };
 
-        Ok(())
+        Ok(record)
+    }
+}
+
+/// A pull-based reader that drives the XML byte stream through
+/// [`WellProductionParser`], yielding one [`ProductionRecord`] per
+/// `<wcproduction>` element as it closes. This lets large exports stream
+/// straight to a sink with bounded memory; callers wanting the grouped
+/// model can still `collect` the records with [`group_records`].
+struct ProductionReader<B: BufRead> {
+    reader: Reader<B>,
+    parser: WellProductionParser,
+    buf: Vec<u8>,
+}
+
+impl<B: BufRead> ProductionReader<B> {
+    pub fn new(reader: Reader<B>, parser: WellProductionParser) -> Self {
+        ProductionReader {
+            reader,
+            parser,
+            buf: Vec::with_capacity(BUF_SIZE),
+        }
     }
 }
 
-fn write_table(w: &mut impl Write,
+impl<B: BufRead> Iterator for ProductionReader<B> {
+    type Item = Result<ProductionRecord, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            self.buf.clear();
+            match self.reader.read_event(&mut self.buf) {
+                Ok(Event::Eof) => return None,
+                Ok(ev) => match self.parser.process(ev) {
+                    Ok(Some(rec)) => return Some(Ok(rec)),
+                    Ok(None) => continue,
+                    Err(e) => return Some(Err(e)),
+                },
+                Err(e) => return Some(Err(e.into())),
+            }
+        }
+    }
+}
+
+/// Collect streamed records into the grouped `HashMap` model, overwriting
+/// matching `(api, date, phase)` cells in record order.
+fn group_records<I>(records: I)
+      -> Result<HashMap<WellAPI, HashMap<Date, WellProduction>>, Box<dyn Error>>
+      where I: IntoIterator<Item = Result<ProductionRecord, Box<dyn Error>>> {
+    let mut production: HashMap<WellAPI, HashMap<Date, WellProduction>> =
+        HashMap::new();
+    for rec in records {
+        let rec = rec?;
+        let cell = production.entry(rec.api)
+            .or_default()
+            .entry(rec.date)
+            .or_insert_with(WellProduction::new);
+        if rec.oil.is_some() { cell.oil = rec.oil; }
+        if rec.gas.is_some() { cell.gas = rec.gas; }
+        if rec.water.is_some() { cell.water = rec.water; }
+    }
+    Ok(production)
+}
+
+#[derive(Copy, Clone, Debug)]
+enum AggregateMode {
+    None,
+    Annual,
+    Cumulative,
+    FirstMonth,
+}
+
+/// Combine two volumes for summation: `None` counts as zero as long as at
+/// least one operand is present, but two absent cells stay `None` so wells
+/// that never reported a phase are not silently turned into zeros.
+fn add_opt(acc: Option<f64>, v: Option<f64>) -> Option<f64> {
+    match (acc, v) {
+        (None, None) => None,
+        (a, b) => Some(a.unwrap_or(0.0) + b.unwrap_or(0.0)),
+    }
+}
+
+/// Derive a new record set from the parsed production. `Annual` collapses each
+/// well to calendar-year totals (keyed with `month` 0); `Cumulative` emits a
+/// running total per month ordered by `(year, month)`; `FirstMonth` keeps only
+/// each well's earliest reporting month. `None` returns `None` so the caller
+/// can write the original map without copying it. The result flows into the
+/// same [`ProductionSink`]s as raw records.
+fn aggregate(production: &HashMap<WellAPI, HashMap<Date, WellProduction>>,
+  mode: AggregateMode)
+  -> Option<HashMap<WellAPI, HashMap<Date, WellProduction>>> {
+    match mode {
+        AggregateMode::None => None,
+
+        AggregateMode::Annual => Some({
+            let mut out = HashMap::new();
+            for (api, by_date) in production {
+                let mut by_year: HashMap<u16, WellProduction> = HashMap::new();
+                for (date, rec) in by_date {
+                    let acc = by_year.entry(date.year)
+                        .or_insert_with(WellProduction::new);
+                    acc.oil = add_opt(acc.oil, rec.oil);
+                    acc.gas = add_opt(acc.gas, rec.gas);
+                    acc.water = add_opt(acc.water, rec.water);
+                }
+
+                let well_out = out.entry(*api).or_default();
+                for (year, rec) in by_year {
+                    well_out.insert(Date { year, month: 0 }, rec);
+                }
+            }
+            out
+        }),
+
+        AggregateMode::Cumulative => Some({
+            let mut out = HashMap::new();
+            for (api, by_date) in production {
+                let mut months: Vec<(&Date, &WellProduction)> =
+                    by_date.iter().collect();
+                months.sort_by_key(|(d, _)| (d.year, d.month));
+
+                let mut running = WellProduction::new();
+                let well_out = out.entry(*api).or_default();
+                for (date, rec) in months {
+                    running.oil = add_opt(running.oil, rec.oil);
+                    running.gas = add_opt(running.gas, rec.gas);
+                    running.water = add_opt(running.water, rec.water);
+                    well_out.insert(*date, running.clone());
+                }
+            }
+            out
+        }),
+
+        AggregateMode::FirstMonth => Some({
+            let mut out = HashMap::new();
+            for (api, by_date) in production {
+                let first = by_date.iter()
+                    .min_by_key(|(d, _)| (d.year, d.month));
+                if let Some((date, rec)) = first {
+                    out.entry(*api)
+                        .or_default()
+                        .insert(*date, rec.clone());
+                }
+            }
+            out
+        }),
+    }
+}
+
+fn write_production(sink: &mut dyn ProductionSink,
   production: &HashMap<WellAPI, HashMap<Date, WellProduction>>
   ) -> io::Result<()> {
-    write!(w, "api\tyear\tmonth\toil\tgas\twater\n")?;
     for (api, by_date) in production {
         for (date, vols) in by_date {
-            write!(w, "{}\t{}\t{}", api, date.year, date.month)?;
+            sink.write_record(*api, *date, vols)?;
+        }
+    }
+    sink.finish()
+}
 
-            if let Some(oil) = vols.oil {
-                write!(w, "\t{}", oil)?;
-            } else {
-                write!(w, "\t")?;
-            }
+/// Merge `src` into `dst`, with `src` volumes overwriting matching
+/// `(api, date, phase)` cells. Absent (`None`) source cells leave the
+/// destination untouched so partial `.mpk` files compose cleanly.
+fn merge_into(dst: &mut HashMap<WellAPI, HashMap<Date, WellProduction>>,
+  src: HashMap<WellAPI, HashMap<Date, WellProduction>>) {
+    for (api, by_date) in src {
+        for (date, rec) in by_date {
+            let cell = dst.entry(api)
+                .or_default()
+                .entry(date)
+                .or_insert_with(WellProduction::new);
+            if rec.oil.is_some() { cell.oil = rec.oil; }
+            if rec.gas.is_some() { cell.gas = rec.gas; }
+            if rec.water.is_some() { cell.water = rec.water; }
+        }
+    }
+}
 
-            if let Some(gas) = vols.gas {
-                write!(w, "\t{}", gas)?;
-            } else {
-                write!(w, "\t")?;
-            }
+/// Concatenate every XML member of a zip archive, in index order, into one
+/// charset-decoded buffered reader. A single-entry archive is taken verbatim
+/// (matching the tool's original behavior regardless of member name); in a
+/// multi-entry archive only `.xml` members are included.
+fn zip_concat<R: Read + Seek>(mut zip: ZipArchive<R>)
+      -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    let only = zip.len() == 1;
+    let mut data = Vec::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i)?;
+        if only || entry.name().to_ascii_lowercase().ends_with(".xml") {
+            entry.read_to_end(&mut data)?;
+        }
+    }
+    Ok(Box::new(BufReader::new(DecodeReaderBytes::new(Cursor::new(data)))))
+}
 
-            if let Some(water) = vols.water {
-                write!(w, "\t{}\n", water)?;
-            } else {
-                write!(w, "\t\n")?;
-            }
+/// Dispatch a seekable byte source to the right decompressor by sniffing its
+/// magic bytes: gzip (`1f 8b`), zip (`PK\x03\x04`), or raw XML. The result is
+/// always routed through `DecodeReaderBytes` so charset detection is applied
+/// before `quick_xml` sees the stream.
+fn decode_source<R: Read + Seek + 'static>(mut src: R)
+      -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    let mut magic = [0u8; 4];
+    let mut filled = 0;
+    while filled < magic.len() {
+        let n = src.read(&mut magic[filled..])?;
+        if n == 0 {
+            break;
         }
+        filled += n;
+    }
+    src.seek(SeekFrom::Start(0))?;
+
+    if filled >= 2 && magic[0] == 0x1f && magic[1] == 0x8b {
+        Ok(Box::new(BufReader::new(
+            DecodeReaderBytes::new(MultiGzDecoder::new(src)))))
+    } else if filled >= 4 && &magic == b"PK\x03\x04" {
+        zip_concat(ZipArchive::new(src)?)
+    } else {
+        Ok(Box::new(BufReader::new(DecodeReaderBytes::new(src))))
     }
-    Ok(())
 }
 
-const EDDY_COUNTY: u16 = 15;
+/// Open a production source behind a single `BufRead`, accepting raw `.xml`,
+/// gzip `.xml.gz`, single- or multi-entry zip archives, and `-` for stdin.
+fn open_production_source(path: &str)
+      -> Result<Box<dyn BufRead>, Box<dyn Error>> {
+    if path == "-" {
+        let mut bytes = Vec::new();
+        io::stdin().lock().read_to_end(&mut bytes)?;
+        decode_source(Cursor::new(bytes))
+    } else {
+        decode_source(File::open(path)?)
+    }
+}
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let path = env::args().nth(1).ok_or("no filename provided")?;
-    let zipfile = File::open(path)?;
-    let mut zip = ZipArchive::new(zipfile)?;
+    let mut path = None;
+    let mut format = "tsv".to_string();
+    let mut merges = Vec::new();
+    let mut states: HashSet<u8> = HashSet::new();
+    let mut counties: HashSet<u16> = HashSet::new();
+    let mut apis: HashSet<WellAPI> = HashSet::new();
+    let mut from = None;
+    let mut to = None;
+    let mut aggregate_mode = AggregateMode::None;
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--format" => {
+                format = args.next().ok_or("--format requires an argument")?;
+            },
+            "--merge" => {
+                merges.push(args.next()
+                    .ok_or("--merge requires an argument")?);
+            },
+            "--state" => {
+                states.insert(args.next()
+                    .ok_or("--state requires an argument")?.parse()?);
+            },
+            "--county" => {
+                counties.insert(args.next()
+                    .ok_or("--county requires an argument")?.parse()?);
+            },
+            "--api" => {
+                apis.insert(args.next()
+                    .ok_or("--api requires an argument")?.parse()?);
+            },
+            "--from" => {
+                from = Some(args.next()
+                    .ok_or("--from requires an argument")?.parse()?);
+            },
+            "--to" => {
+                to = Some(args.next()
+                    .ok_or("--to requires an argument")?.parse()?);
+            },
+            "--aggregate" => {
+                aggregate_mode = match args.next()
+                      .ok_or("--aggregate requires an argument")?.as_str() {
+                    "none" => AggregateMode::None,
+                    "annual" => AggregateMode::Annual,
+                    "cumulative" => AggregateMode::Cumulative,
+                    "first" => AggregateMode::FirstMonth,
+                    other => Err(format!("unknown aggregate mode: {}", other))?,
+                };
+            },
+            _ => path = Some(arg),
+        }
+    }
 
-    if zip.len() != 1 {
-        Err("expected one file in zip archive")?;
+    let date_range = match (from, to) {
+        (None, None) => None,
+        (from, to) => Some((
+            from.unwrap_or(Date { year: 0, month: 1 }),
+            to.unwrap_or(Date { year: u16::MAX, month: 12 }),
+        )),
+    };
+    let filter = ProductionFilter {
+        states: if states.is_empty() { None } else { Some(states) },
+        counties: if counties.is_empty() { None } else { Some(counties) },
+        apis: if apis.is_empty() { None } else { Some(apis) },
+        date_range,
+    };
+
+    let mut prod = HashMap::new();
+
+    if let Some(path) = path {
+        let xmlfile = Reader::from_reader(open_production_source(&path)?);
+
+        let prodparser = WellProductionParser::with_filter(filter);
+        prod = group_records(ProductionReader::new(xmlfile, prodparser))?;
+    } else if merges.is_empty() {
+        Err("no filename provided")?;
     }
 
-    let xmlfile = zip.by_index(0)?;
-    let xmlfile = BufReader::new(DecodeReaderBytes::new(xmlfile));
-    let mut xmlfile = Reader::from_reader(xmlfile);
-
-    let mut prodparser = WellProductionParser::with_predicate(
-        &|api: WellAPI| api.county == EDDY_COUNTY);
-    let mut buf = Vec::with_capacity(BUF_SIZE);
-    loop {
-        match xmlfile.read_event(&mut buf)? {
-            Event::Eof => break,
-            ev => prodparser.process(ev)?,
-        };
-        buf.clear();
+    for merge in merges {
+        let mut f = BufReader::new(File::open(merge)?);
+        merge_into(&mut prod, read_msgpack(&mut f)?);
     }
 
-    let prod = prodparser.finish();
-    write_table(&mut io::stdout(), &prod)?;
+    let aggregated = aggregate(&prod, aggregate_mode);
+
+    let stdout = io::stdout();
+    let out = stdout.lock();
+    let mut sink: Box<dyn ProductionSink> = match format.as_str() {
+        "tsv" => Box::new(TsvSink::new(out)?),
+        "csv" => Box::new(CsvSink::new(out)?),
+        "jsonl" => Box::new(JsonlSink::new(out)?),
+        "mpk" => Box::new(MsgpackSink::new(out)?),
+        other => Err(format!("unknown output format: {}", other))?,
+    };
+    write_production(sink.as_mut(), aggregated.as_ref().unwrap_or(&prod))?;
 
     Ok(())
 }